@@ -2,11 +2,155 @@ use std::collections::HashSet;
 use std::env;
 use std::io::{BufRead, BufReader, Read, Write};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::process::{Command, Stdio};
+use std::path::PathBuf;
+use std::process::Command;
 use std::sync::mpsc::{channel, Sender};
 use std::thread;
 
+use serde::{Deserialize, Serialize};
+
 const SOCKET_PATH: &str = "/tmp/naaw-socket";
+const DEFAULT_GROUP: &str = "default";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Hooks {
+    on_tag: Option<String>,
+    on_untag: Option<String>,
+    on_show: Option<String>,
+    on_hide: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct Config {
+    socket_path: String,
+    tagged_border_width: usize,
+    tagged_border_color: Option<String>,
+    tag_shown_by_default: bool,
+    hooks: Hooks,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            socket_path: String::from(SOCKET_PATH),
+            tagged_border_width: 3,
+            tagged_border_color: None,
+            tag_shown_by_default: true,
+            hooks: Hooks::default(),
+        }
+    }
+}
+
+/// Spawns `command` through a shell with the event payload exported in the
+/// environment. Fire-and-forget: the hook's own output/exit status isn't
+/// naaw's concern, so failures to even spawn it are just logged.
+fn run_hook(command: &Option<String>, node: &str, group: &str, status: &str) {
+    let command = match command {
+        Some(command) => command,
+        None => return,
+    };
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("NAAW_NODE", node)
+        .env("NAAW_GROUP", group)
+        .env("NAAW_STATUS", status)
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            thread::spawn(move || {
+                let _ = child.wait();
+            });
+        }
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+fn config_path() -> PathBuf {
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(env::var("HOME").unwrap()).join(".config"));
+    config_home.join("naaw").join("config.toml")
+}
+
+fn load_config() -> Config {
+    let path = config_path();
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => return Config::default(),
+    };
+    match toml::from_str(&contents) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Couldn't parse {}: {}", path.display(), err);
+            Config::default()
+        }
+    }
+}
+
+fn state_path() -> PathBuf {
+    let state_home = env::var("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            PathBuf::from(env::var("HOME").unwrap())
+                .join(".local")
+                .join("state")
+        });
+    state_home.join("naaw").join("state.msgpack")
+}
+
+fn save_state(state: &state::State) {
+    let path = state_path();
+    if let Some(parent) = path.parent() {
+        if let Err(err) = std::fs::create_dir_all(parent) {
+            eprintln!("Couldn't create {}: {}", parent.display(), err);
+            return;
+        }
+    }
+    match rmp_serde::to_vec(state) {
+        Ok(bytes) => {
+            if let Err(err) = std::fs::write(&path, bytes) {
+                eprintln!("Couldn't persist state to {}: {}", path.display(), err);
+            }
+        }
+        Err(err) => eprintln!("Couldn't serialize state: {}", err),
+    }
+}
+
+/// Loads the persisted state (or a fresh one, if there's none yet) and drops
+/// any tagged node that bspwm no longer knows about, since the server may
+/// have been down for a while when nodes came and went.
+fn load_state(config: &Config) -> state::State {
+    let path = state_path();
+    let mut state = match std::fs::read(&path) {
+        Ok(bytes) => rmp_serde::from_slice(&bytes).unwrap_or_else(|err| {
+            eprintln!("Couldn't parse {}: {}", path.display(), err);
+            state::State::new(config.tag_shown_by_default)
+        }),
+        Err(_) => state::State::new(config.tag_shown_by_default),
+    };
+    state.set_default_tag_shown(config.tag_shown_by_default);
+    let existing_nodes: HashSet<Node> = bspwm::send(&["query", "-N"])
+        .lines()
+        .map(|line| Node(String::from(line)))
+        .collect();
+    state.retain_nodes(&existing_nodes);
+    state
+}
+
+/// Re-applies the border width and shown/hidden flag naaw would have set
+/// live, so a restored model matches what's actually on screen.
+fn reapply_visuals(state: &state::State, config: &Config) {
+    for group in state.group_names() {
+        let shown = state.is_tag_shown(group);
+        for node in state.tagged(group) {
+            bspc_set_border_width(node, config.tagged_border_width);
+            bspc_set_hidden(node, !shown);
+        }
+    }
+}
 
 #[derive(Debug)]
 enum BspcSubCommand {
@@ -15,22 +159,67 @@ enum BspcSubCommand {
 }
 
 impl BspcSubCommand {
-    fn name(&self) -> &str {
-        match self {
-            BspcSubCommand::NodeAdd => "node_add",
-            BspcSubCommand::NodeRemove => "node_remove",
-        }
-    }
-
     fn node_position(&self) -> usize {
         match self {
             BspcSubCommand::NodeAdd => 4,
             BspcSubCommand::NodeRemove => 3,
         }
     }
+
+    fn from_event_line(line: &str) -> Option<Self> {
+        match line.split(' ').next()? {
+            "node_add" => Some(BspcSubCommand::NodeAdd),
+            "node_remove" => Some(BspcSubCommand::NodeRemove),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+mod bspwm {
+    use super::*;
+
+    fn socket_path() -> String {
+        env::var("BSPWM_SOCKET").unwrap_or_else(|_| String::from("/tmp/bspwm_0_0-socket"))
+    }
+
+    fn connect() -> UnixStream {
+        UnixStream::connect(socket_path()).expect("couldn't connect to bspwm socket")
+    }
+
+    fn write_message(stream: &mut UnixStream, args: &[&str]) {
+        let mut message = Vec::new();
+        for arg in args {
+            message.extend_from_slice(arg.as_bytes());
+            message.push(0);
+        }
+        stream.write_all(&message).unwrap();
+    }
+
+    /// Sends a single command to bspwm and returns its reply, or panics if
+    /// bspwm reported a failure (a reply starting with byte 0x07).
+    pub fn send(args: &[&str]) -> String {
+        let mut stream = connect();
+        write_message(&mut stream, args);
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        if reply.as_bytes().first() == Some(&0x07) {
+            panic!("bspwm error: {}", reply[1..].trim());
+        }
+        reply
+    }
+
+    /// Opens a dedicated connection subscribed to `node_add`/`node_remove`
+    /// and returns the newline-delimited event lines, in the same format
+    /// `BspcSubCommand::from_event_line` and the existing `node_position()`
+    /// parsing already expect.
+    pub fn subscribe() -> impl Iterator<Item = std::io::Result<String>> {
+        let mut stream = connect();
+        write_message(&mut stream, &["subscribe", "node_add", "node_remove"]);
+        BufReader::new(stream).lines()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct Node(String);
 
 mod state {
@@ -41,60 +230,116 @@ mod state {
         Untagged,
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Serialize, Deserialize)]
     pub struct State {
-        tagged_nodes: HashSet<Node>,
+        groups: std::collections::HashMap<String, HashSet<Node>>,
+        group_shown: std::collections::HashMap<String, bool>,
         untagged_nodes: HashSet<Node>,
-        tag_shown: bool,
+        #[serde(skip)]
+        default_tag_shown: bool,
     }
 
     impl State {
-        pub fn new() -> Self {
+        pub fn new(default_tag_shown: bool) -> Self {
             Self {
-                tagged_nodes: HashSet::new(),
+                groups: std::collections::HashMap::new(),
+                group_shown: std::collections::HashMap::new(),
                 untagged_nodes: HashSet::new(),
-                tag_shown: true,
+                default_tag_shown,
             }
         }
 
+        pub fn set_default_tag_shown(&mut self, default_tag_shown: bool) {
+            self.default_tag_shown = default_tag_shown;
+        }
+
+        /// Drops any node the caller no longer considers real, e.g. because
+        /// it went away while naaw wasn't running to see it happen.
+        pub fn retain_nodes(&mut self, existing: &HashSet<Node>) {
+            self.untagged_nodes.retain(|node| existing.contains(node));
+            for nodes in self.groups.values_mut() {
+                nodes.retain(|node| existing.contains(node));
+            }
+        }
+
+        pub fn group_names(&self) -> impl Iterator<Item = &String> {
+            self.groups.keys()
+        }
+
         pub fn add_node(&mut self, node: Node) {
             self.untagged_nodes.insert(node);
         }
 
         pub fn remove_node(&mut self, node: &Node) {
-            self.tagged_nodes.remove(node);
             self.untagged_nodes.remove(node);
+            for nodes in self.groups.values_mut() {
+                nodes.remove(node);
+            }
         }
 
-        pub fn toggle_tag(&mut self, node: Node) -> TagStatus {
-            if self.tagged_nodes.contains(&node) {
-                self.tagged_nodes.remove(&node);
+        pub fn toggle_tag(&mut self, group: &str, node: Node) -> TagStatus {
+            let nodes = self.groups.entry(group.to_string()).or_default();
+            if nodes.contains(&node) {
+                nodes.remove(&node);
                 self.untagged_nodes.insert(node);
                 TagStatus::Untagged
             } else {
                 self.untagged_nodes.remove(&node);
-                self.tagged_nodes.insert(node);
+                nodes.insert(node);
                 TagStatus::Tagged
             }
         }
 
-        pub fn is_tag_shown(&self) -> bool {
-            self.tag_shown
+        pub fn is_tag_shown(&self, group: &str) -> bool {
+            *self
+                .group_shown
+                .get(group)
+                .unwrap_or(&self.default_tag_shown)
         }
 
-        pub fn toggle_tag_visibility(&mut self) -> impl std::iter::IntoIterator<Item = &Node> {
-            self.tag_shown = !self.tag_shown;
-            self.tagged_nodes.iter().clone()
+        /// Flips whether `group`'s tagged nodes should be visible, returning
+        /// the group's new shown state along with its tagged nodes.
+        pub fn toggle_tag_visibility(&mut self, group: &str) -> (bool, Vec<Node>) {
+            let default_tag_shown = self.default_tag_shown;
+            let shown = self
+                .group_shown
+                .entry(group.to_string())
+                .or_insert(default_tag_shown);
+            *shown = !*shown;
+            let nodes = self
+                .groups
+                .get(group)
+                .map(|nodes| nodes.iter().cloned().collect())
+                .unwrap_or_default();
+            (*shown, nodes)
+        }
+
+        pub fn tagged(&self, group: &str) -> impl Iterator<Item = &Node> {
+            self.groups.get(group).into_iter().flatten()
         }
     }
 }
 
-#[derive(Debug)]
 enum Event {
     AddNode(Node),
     RemoveNode(Node),
-    TagNode(Node),
-    ShowTag,
+    TagNode(String, Node),
+    ShowTag(String),
+    ListTagged(String, Sender<String>),
+}
+
+impl std::fmt::Debug for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Event::AddNode(node) => f.debug_tuple("AddNode").field(node).finish(),
+            Event::RemoveNode(node) => f.debug_tuple("RemoveNode").field(node).finish(),
+            Event::TagNode(group, node) => {
+                f.debug_tuple("TagNode").field(group).field(node).finish()
+            }
+            Event::ShowTag(group) => f.debug_tuple("ShowTag").field(group).finish(),
+            Event::ListTagged(group, _) => f.debug_tuple("ListTagged").field(group).finish(),
+        }
+    }
 }
 
 impl Event {
@@ -106,24 +351,20 @@ impl Event {
     }
 }
 
-fn subscribe_bspc(sub_command: BspcSubCommand, tx: Sender<Event>) {
+fn subscribe_bspc(tx: Sender<Event>) {
     thread::spawn(move || {
-        let output = Command::new("bspc")
-            .arg("subscribe")
-            .arg(sub_command.name())
-            .stdout(Stdio::piped())
-            .spawn()
-            .unwrap()
-            .stdout
-            .unwrap();
-        for line in BufReader::new(output).lines() {
+        for line in bspwm::subscribe() {
             let line = match line {
                 Err(err) => {
-                    eprintln!("{}", err.to_string());
+                    eprintln!("{}", err);
                     continue;
                 }
                 Ok(l) => l,
             };
+            let sub_command = match BspcSubCommand::from_event_line(&line) {
+                None => continue,
+                Some(sub_command) => sub_command,
+            };
             let node_id = match line.split(' ').nth(sub_command.node_position()) {
                 None => {
                     eprintln!("Couldn't parse bspc output");
@@ -132,7 +373,7 @@ fn subscribe_bspc(sub_command: BspcSubCommand, tx: Sender<Event>) {
                 Some(node) => node,
             };
             if let Err(err) = tx.send(Event::from_bspc(&sub_command, node_id)) {
-                eprintln!("{}", err.to_string());
+                eprintln!("{}", err);
                 continue;
             }
         }
@@ -142,26 +383,44 @@ fn subscribe_bspc(sub_command: BspcSubCommand, tx: Sender<Event>) {
 fn handle_client_stream(mut stream: UnixStream, tx: Sender<Event>) {
     let mut message = String::new();
     stream.read_to_string(&mut message).unwrap();
-    if &message == "show" {
-        tx.send(Event::ShowTag).unwrap();
-        return;
-    }
-    if let Some(node) = message.strip_prefix("tag ") {
-        tx.send(Event::TagNode(Node(String::from(node)))).unwrap();
-        return;
+    let mut parts = message.splitn(3, ' ');
+    let response = match parts.next() {
+        Some("show") => {
+            let group = parts.next().unwrap_or(DEFAULT_GROUP);
+            tx.send(Event::ShowTag(group.to_string())).unwrap();
+            String::from("+OK\n")
+        }
+        Some("list") => {
+            let group = parts.next().unwrap_or(DEFAULT_GROUP);
+            let (reply_tx, reply_rx) = channel();
+            tx.send(Event::ListTagged(group.to_string(), reply_tx))
+                .unwrap();
+            reply_rx.recv().unwrap()
+        }
+        Some("tag") => match (parts.next(), parts.next()) {
+            (Some(group), Some(node)) => {
+                tx.send(Event::TagNode(group.to_string(), Node(String::from(node))))
+                    .unwrap();
+                String::from("+OK\n")
+            }
+            _ => format!("-ERR unsupported message {}\n", message),
+        },
+        _ => format!("-ERR unsupported message {}\n", message),
+    };
+    if let Err(err) = stream.write_all(response.as_bytes()) {
+        eprintln!("{}", err);
     }
-    eprintln!("Unsupported message {}", message);
 }
 
-fn subscribe_client(tx: Sender<Event>) {
-    let _ = std::fs::remove_file(SOCKET_PATH);
-    let listener = UnixListener::bind(SOCKET_PATH).unwrap();
+fn subscribe_client(socket_path: &str, tx: Sender<Event>) {
+    let _ = std::fs::remove_file(socket_path);
+    let listener = UnixListener::bind(socket_path).unwrap();
     thread::spawn(move || {
         for stream in listener.incoming() {
             match stream {
                 Ok(stream) => handle_client_stream(stream, tx.clone()),
                 Err(err) => {
-                    eprintln!("{}", err.to_string());
+                    eprintln!("{}", err);
                     continue;
                 }
             }
@@ -170,48 +429,51 @@ fn subscribe_client(tx: Sender<Event>) {
 }
 
 fn bspc_toggle_visibility(node: &Node) {
-    Command::new("bspc")
-        .arg("node")
-        .arg(node.0.as_str())
-        .arg("-g")
-        .arg("hidden")
-        .output()
-        .unwrap();
+    bspwm::send(&["node", node.0.as_str(), "-g", "hidden"]);
+}
+
+fn bspc_set_hidden(node: &Node, hidden: bool) {
+    let flag = if hidden { "hidden=on" } else { "hidden=off" };
+    bspwm::send(&["node", node.0.as_str(), "-g", flag]);
 }
 
 fn bspc_set_border_width(node: &Node, width: usize) {
-    Command::new("bspc")
-        .arg("config")
-        .arg("-n")
-        .arg(node.0.as_str())
-        .arg("border_width")
-        .arg(width.to_string())
-        .output()
-        .unwrap();
+    bspwm::send(&[
+        "config",
+        "-n",
+        node.0.as_str(),
+        "border_width",
+        &width.to_string(),
+    ]);
 }
 
 fn bspc_reset_border_width(node: &Node) {
-    let output = Command::new("bspc")
-        .arg("config")
-        .arg("border_width")
-        .output()
-        .unwrap();
-    let default_border_width = std::str::from_utf8(output.stdout.as_slice())
-        .unwrap()
+    let default_border_width = bspwm::send(&["config", "border_width"])
         .trim()
         .parse()
         .unwrap();
     bspc_set_border_width(node, default_border_width);
 }
 
-fn server() {
+/// bspwm only exposes `active_border_color` as a global/monitor-wide
+/// setting, not per-node like `border_width`, so a focused tagged node
+/// borrows the whole window manager's "active" color instead of getting
+/// one of its own.
+fn bspc_set_active_border_color(color: &str) {
+    bspwm::send(&["config", "active_border_color", color]);
+}
+
+fn server(config: Config) {
     let (tx, rx) = channel::<Event>();
 
-    let mut state = state::State::new();
+    let mut state = load_state(&config);
+    reapply_visuals(&state, &config);
+    if let Some(color) = &config.tagged_border_color {
+        bspc_set_active_border_color(color);
+    }
 
-    subscribe_bspc(BspcSubCommand::NodeAdd, tx.clone());
-    subscribe_bspc(BspcSubCommand::NodeRemove, tx.clone());
-    subscribe_client(tx);
+    subscribe_bspc(tx.clone());
+    subscribe_client(&config.socket_path, tx);
 
     for state_change in &rx {
         dbg!(&state_change);
@@ -222,56 +484,80 @@ fn server() {
             Event::RemoveNode(node) => {
                 state.remove_node(&node);
             }
-            Event::TagNode(node) => match state.toggle_tag(node.clone()) {
+            Event::TagNode(group, node) => match state.toggle_tag(&group, node.clone()) {
                 state::TagStatus::Tagged => {
-                    bspc_set_border_width(&node, 3);
-                    if !state.is_tag_shown() {
+                    bspc_set_border_width(&node, config.tagged_border_width);
+                    if !state.is_tag_shown(&group) {
                         bspc_toggle_visibility(&node);
                     }
+                    run_hook(&config.hooks.on_tag, &node.0, &group, "tagged");
                 }
                 state::TagStatus::Untagged => {
                     bspc_reset_border_width(&node);
+                    run_hook(&config.hooks.on_untag, &node.0, &group, "untagged");
                 }
             },
-            Event::ShowTag => {
-                for node in state.toggle_tag_visibility() {
+            Event::ShowTag(group) => {
+                let (now_shown, nodes) = state.toggle_tag_visibility(&group);
+                let (hook, status) = if now_shown {
+                    (&config.hooks.on_show, "shown")
+                } else {
+                    (&config.hooks.on_hide, "hidden")
+                };
+                for node in &nodes {
                     bspc_toggle_visibility(node);
+                    run_hook(hook, &node.0, &group, status);
                 }
             }
+            Event::ListTagged(group, reply_tx) => {
+                let body: String = state
+                    .tagged(&group)
+                    .map(|node| format!("{}\n", node.0))
+                    .collect();
+                let _ = reply_tx.send(body);
+            }
         };
         dbg!(&state);
+        save_state(&state);
     }
 }
 
-fn send_client_message(message: &str) {
-    let mut stream = UnixStream::connect(SOCKET_PATH).unwrap();
+fn send_client_message(socket_path: &str, message: &str) -> String {
+    let mut stream = UnixStream::connect(socket_path).unwrap();
     stream.write_all(message.as_bytes()).unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    if let Some(reason) = response.strip_prefix("-ERR ") {
+        eprint!("{}", reason);
+    }
+    response
 }
 
-fn tag() {
-    let output = Command::new("bspc")
-        .arg("query")
-        .arg("-N")
-        .arg("focused")
-        .arg("-n")
-        .output()
-        .unwrap();
-    let node = std::str::from_utf8(output.stdout.as_slice())
-        .unwrap()
-        .trim();
-    send_client_message(&format!("tag {}", node));
+fn tag(config: &Config, group: &str) {
+    let node = bspwm::send(&["query", "-N", "focused", "-n"]);
+    send_client_message(&config.socket_path, &format!("tag {} {}", group, node.trim()));
+}
+
+fn show(config: &Config, group: &str) {
+    send_client_message(&config.socket_path, &format!("show {}", group));
 }
 
-fn show() {
-    send_client_message("show")
+fn list(config: &Config, group: &str) {
+    let response = send_client_message(&config.socket_path, &format!("list {}", group));
+    print!("{}", response);
 }
 
 fn main() {
+    let config = load_config();
     let mut args = env::args().skip(1);
-    match args.nth(0).unwrap().as_str() {
-        "server" => server(),
-        "tag" => tag(),
-        "show" => show(),
+    let command = args.next().unwrap();
+    let group = args.next().unwrap_or_else(|| String::from(DEFAULT_GROUP));
+    match command.as_str() {
+        "server" => server(config),
+        "tag" => tag(&config, &group),
+        "show" => show(&config, &group),
+        "list" => list(&config, &group),
         _ => panic!("wrong argument"),
     }
 }